@@ -0,0 +1,108 @@
+// Standalone async worker that drains pending `storage_request` rows against
+// R2 and reports outcomes back through the `submit_storage_response` reducer.
+//
+// This has to live outside the module: reducers run synchronously inside the
+// SpacetimeDB WASM sandbox with no outbound network access, which is exactly
+// why `delete_track` and `request_stream_url` can only enqueue work instead
+// of doing it themselves. This binary connects like any other client,
+// subscribes to `storage_request`, and reuses `get_r2_client`/`get_r2_bucket`
+// from the module crate to talk to R2 with the same client `init` probes.
+//
+// Requires bindings generated once via:
+//   spacetime generate --lang rust --out-dir src/bin/module_bindings --project-path .
+mod module_bindings;
+
+use module_bindings::{
+    DbConnection, EventContext, StorageOperation, StorageRequest, StorageRequestTableAccess,
+};
+use spacetimedb_sdk::{credentials, DbContext, Table};
+use spacewhale::{get_r2_bucket, get_r2_client, PRESIGNED_URL_TTL_SECONDS};
+use std::time::Duration;
+
+const MODULE_NAME: &str = "spacewhale";
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let uri = std::env::var("SPACETIMEDB_URI").unwrap_or_else(|_| "http://localhost:3000".to_string());
+
+    let connection = DbConnection::builder()
+        .with_module_name(MODULE_NAME)
+        .with_uri(uri)
+        .with_token(credentials::load(MODULE_NAME).ok().flatten())
+        .on_connect_error(|_ctx, err| {
+            eprintln!("Storage worker failed to connect: {}", err);
+            std::process::exit(1);
+        })
+        .build()
+        .expect("failed to connect storage worker to spacetimedb");
+
+    connection.db.storage_request().on_insert(on_storage_request);
+
+    connection
+        .subscription_builder()
+        .on_error(|_ctx, err| eprintln!("Storage worker subscription failed: {}", err))
+        .subscribe(["SELECT * FROM storage_request"]);
+
+    connection.run_async().await.expect("storage worker connection closed");
+}
+
+fn on_storage_request(ctx: &EventContext, request: &StorageRequest) {
+    let request = request.clone();
+    let reducers = ctx.reducers.clone();
+
+    tokio::spawn(async move {
+        let (presigned_url, expires_at, error) = match process_request(&request).await {
+            Ok((url, expires_at)) => (url, expires_at, None),
+            Err(e) => (None, None, Some(e)),
+        };
+
+        if let Err(e) = reducers.submit_storage_response(request.id.clone(), presigned_url, expires_at, error) {
+            eprintln!("Failed to submit storage response for {}: {}", request.id, e);
+        }
+    });
+}
+
+// Performs the actual R2 call for a single request and returns
+// (presigned_url, expires_at) on success, or an error string on failure.
+async fn process_request(request: &StorageRequest) -> Result<(Option<String>, Option<u64>), String> {
+    let client = get_r2_client()?;
+    let bucket = get_r2_bucket()?;
+
+    match request.operation {
+        StorageOperation::GetPresignedUrl => {
+            let presigning_config = aws_sdk_s3::presigning::PresigningConfig::expires_in(
+                Duration::from_secs(PRESIGNED_URL_TTL_SECONDS),
+            )
+            .map_err(|e| e.to_string())?;
+
+            let presigned = client.get_object()
+                .bucket(&bucket)
+                .key(&request.file_path)
+                .presigned(presigning_config)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            let expires_at = current_timestamp() + PRESIGNED_URL_TTL_SECONDS;
+            Ok((Some(presigned.uri().to_string()), Some(expires_at)))
+        }
+        StorageOperation::DeleteObject => {
+            client.delete_object()
+                .bucket(&bucket)
+                .key(&request.file_path)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            Ok((None, None))
+        }
+    }
+}
+
+fn current_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
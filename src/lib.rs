@@ -1,11 +1,27 @@
-use spacetimedb::{table, reducer, Table, ReducerContext, Identity};
+use spacetimedb::{table, reducer, Table, ReducerContext, Identity, SpacetimeType};
 use log::{info, error};
 use aws_sdk_s3::{Client as S3Client};
 use aws_sdk_s3::config::{Region, Credentials};
 use std::env;
+use std::collections::{HashMap, HashSet};
 use rand::{thread_rng, Rng};
 use rand::distributions::Alphanumeric;
 
+// How long a minted GET url for a track's bytes stays valid. Shared with the
+// out-of-module storage worker (src/bin/storage_worker.rs) that mints them.
+pub const PRESIGNED_URL_TTL_SECONDS: u64 = 3600;
+
+// Jaccard similarity over trigram sets that a fuzzy match must exceed to be kept
+const FUZZY_SEARCH_THRESHOLD: f64 = 0.3;
+
+// How many of the caller's most-favorited genres count as "top genres" when scoring recommendations
+const TOP_GENRE_COUNT: usize = 3;
+const RECOMMENDATION_GENRE_WEIGHT: f64 = 3.0;
+const RECOMMENDATION_ARTIST_WEIGHT: f64 = 2.0;
+const RECOMMENDATION_ALBUM_WEIGHT: f64 = 1.0;
+const RECOMMENDATION_RECENCY_BONUS: f64 = 0.5;
+const RECOMMENDATION_RECENCY_WINDOW_SECONDS: u64 = 30 * 24 * 60 * 60;
+
 // Helper function to generate a random ID
 fn generate_id() -> String {
     let rand_string: String = thread_rng()
@@ -87,8 +103,269 @@ pub struct UserFavorite {
     pub date_added: u64,
 }
 
-// Initialize R2 client
-fn get_r2_client() -> Result<S3Client, String> {
+// PlayEvent table - one row per completed (or partial) listen, for scrobbling/stats
+#[table(name = play_event, public)]
+#[derive(Clone)]
+pub struct PlayEvent {
+    #[primary_key]
+    pub id: String,
+    pub user_id: String,
+    pub track_id: String,
+    pub played_at: u64,
+    pub ms_played: u32,
+}
+
+// TopTrack table - the leaderboard computed by get_top_tracks for a rolling window
+#[table(name = top_track, public)]
+#[derive(Clone)]
+pub struct TopTrack {
+    #[primary_key]
+    pub id: String,
+    pub user_id: String,
+    pub track_id: String,
+    pub play_count: u32,
+    pub total_ms_played: u64,
+}
+
+// Recommendation table - tracks suggested to a user from their favorites graph
+#[table(name = recommendation, public)]
+#[derive(Clone)]
+pub struct Recommendation {
+    #[primary_key]
+    pub id: String,
+    pub user_id: String,
+    pub track_id: String,
+    pub score: f64,
+}
+
+// SearchResult table - ranked fuzzy search hits for a given caller
+#[table(name = search_result, public)]
+#[derive(Clone)]
+pub struct SearchResult {
+    #[primary_key]
+    pub id: String,
+    pub user_id: String,
+    pub track_id: String,
+    pub score: f64,
+    pub date_added: u64,
+}
+
+// Generate the trigram set of a string, padded with two leading spaces and
+// one trailing space so short strings and word edges are represented too.
+fn trigrams(s: &str) -> HashSet<String> {
+    let padded = format!("  {} ", s.to_lowercase());
+    let chars: Vec<char> = padded.chars().collect();
+
+    if chars.len() < 3 {
+        return HashSet::new();
+    }
+
+    chars.windows(3).map(|w| w.iter().collect()).collect()
+}
+
+// Jaccard similarity between two trigram sets: |intersection| / |union|
+fn trigram_similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+
+    let intersection = a.intersection(b).count();
+    let union = a.union(b).count();
+
+    intersection as f64 / union as f64
+}
+
+// How play_next behaves once it runs off the end of the queue
+#[derive(Clone, Copy, PartialEq, SpacetimeType)]
+pub enum RepeatMode {
+    Off,
+    Track,
+    Context,
+}
+
+// PlaybackState table - one row per user holding their now-playing state
+#[table(name = playback_state, public)]
+#[derive(Clone)]
+pub struct PlaybackState {
+    #[primary_key]
+    pub user_id: String,
+    pub current_track_id: Option<String>,
+    pub position_seconds: u32,
+    pub is_playing: bool,
+    pub repeat_mode: RepeatMode,
+    pub shuffle: bool,
+}
+
+// PlayQueue table - the ordered (or shuffled) set of tracks queued for a user
+#[table(name = play_queue, public)]
+#[derive(Clone)]
+pub struct PlayQueue {
+    #[primary_key]
+    pub id: String,
+    pub user_id: String,
+    pub track_id: String,
+    pub position: u32,
+    pub played: bool,
+}
+
+// Build a fresh, unplayed queue for `user_id` from a playlist or the user's favorites.
+// `source_id` is treated as a playlist id first, falling back to "favorites".
+fn populate_play_queue(ctx: &ReducerContext, user_id: &str, source_id: &str) {
+    let play_queue_table = ctx.db.play_queue();
+
+    // Clear any existing queue for this user before loading the new source
+    let existing: Vec<PlayQueue> = play_queue_table.iter()
+        .filter(|q| q.user_id == user_id)
+        .collect();
+
+    for entry in existing {
+        play_queue_table.delete(entry);
+    }
+
+    let mut playlist_tracks: Vec<PlaylistTrack> = ctx.db.playlist_track().iter()
+        .filter(|pt| pt.playlist_id == source_id)
+        .collect();
+    playlist_tracks.sort_by_key(|pt| pt.position);
+
+    let track_ids: Vec<String> = if !playlist_tracks.is_empty() {
+        playlist_tracks.into_iter().map(|pt| pt.track_id).collect()
+    } else {
+        let mut favorites: Vec<UserFavorite> = ctx.db.user_favorite().iter()
+            .filter(|fav| fav.user_id == user_id)
+            .collect();
+        favorites.sort_by_key(|fav| fav.date_added);
+        favorites.into_iter().map(|fav| fav.track_id).collect()
+    };
+
+    for (position, track_id) in track_ids.into_iter().enumerate() {
+        play_queue_table.insert(PlayQueue {
+            id: generate_id(),
+            user_id: user_id.to_string(),
+            track_id,
+            position: position as u32,
+            played: false,
+        });
+    }
+}
+
+// Mark every queue entry for `user_id` as unplayed again, e.g. when the queue
+// wraps around under Context repeat.
+fn reset_play_queue(ctx: &ReducerContext, user_id: &str) {
+    let play_queue_table = ctx.db.play_queue();
+    let entries: Vec<PlayQueue> = play_queue_table.iter()
+        .filter(|q| q.user_id == user_id)
+        .collect();
+
+    for mut entry in entries {
+        let old_entry = entry.clone();
+        entry.played = false;
+        play_queue_table.delete(old_entry);
+        play_queue_table.insert(entry);
+    }
+}
+
+// Pick the next queue entry to play for `user_id`, honoring shuffle and repeat_mode.
+// Returns None when the queue is exhausted and repeat is Off.
+fn advance_queue(ctx: &ReducerContext, user_id: &str, state: &PlaybackState) -> Option<String> {
+    if state.repeat_mode == RepeatMode::Track {
+        return state.current_track_id.clone();
+    }
+
+    let play_queue_table = ctx.db.play_queue();
+    let mut queue: Vec<PlayQueue> = play_queue_table.iter()
+        .filter(|q| q.user_id == user_id)
+        .collect();
+
+    if queue.is_empty() {
+        return None;
+    }
+
+    queue.sort_by_key(|q| q.position);
+
+    let next_entry = if state.shuffle {
+        let unplayed: Vec<&PlayQueue> = queue.iter()
+            .filter(|q| !q.played && Some(&q.track_id) != state.current_track_id.as_ref())
+            .collect();
+        if unplayed.is_empty() {
+            None
+        } else {
+            let index = ctx.rng().gen_range(0..unplayed.len());
+            Some(unplayed[index].clone())
+        }
+    } else {
+        let current_position = queue.iter()
+            .find(|q| Some(&q.track_id) == state.current_track_id.as_ref())
+            .map(|q| q.position);
+
+        match current_position {
+            Some(pos) => queue.iter().find(|q| q.position > pos).cloned(),
+            // current_track_id is None either because playback never started,
+            // or because a prior play_next walked off the end under repeat
+            // Off and stopped. Only the former should restart at the top.
+            None if is_queue_unstarted(&queue) => queue.first().cloned(),
+            None => None,
+        }
+    };
+
+    match next_entry {
+        Some(entry) => Some(mark_played(ctx, entry)),
+        None if state.repeat_mode == RepeatMode::Context => {
+            reset_play_queue(ctx, user_id);
+
+            let mut refreshed: Vec<PlayQueue> = ctx.db.play_queue().iter()
+                .filter(|q| q.user_id == user_id)
+                .collect();
+            refreshed.sort_by_key(|q| q.position);
+
+            refreshed.into_iter().next().map(|entry| mark_played(ctx, entry))
+        }
+        None => None,
+    }
+}
+
+// True if nothing in the queue has been marked played yet, i.e. playback
+// hasn't started rather than having run off the end under repeat Off.
+fn is_queue_unstarted(queue: &[PlayQueue]) -> bool {
+    queue.iter().all(|q| !q.played)
+}
+
+// Mark a queue entry as played and return its track id
+fn mark_played(ctx: &ReducerContext, entry: PlayQueue) -> String {
+    let play_queue_table = ctx.db.play_queue();
+    let track_id = entry.track_id.clone();
+
+    play_queue_table.delete(entry.clone());
+    play_queue_table.insert(PlayQueue { played: true, ..entry });
+
+    track_id
+}
+
+// Pick the previous queue entry relative to the currently playing track.
+// Shuffle has no well-defined "previous", so this always walks queue order.
+fn retreat_queue(ctx: &ReducerContext, user_id: &str, state: &PlaybackState) -> Option<String> {
+    let mut queue: Vec<PlayQueue> = ctx.db.play_queue().iter()
+        .filter(|q| q.user_id == user_id)
+        .collect();
+
+    if queue.is_empty() {
+        return None;
+    }
+
+    queue.sort_by_key(|q| q.position);
+
+    let current_position = queue.iter()
+        .find(|q| Some(&q.track_id) == state.current_track_id.as_ref())
+        .map(|q| q.position);
+
+    match current_position {
+        Some(pos) => queue.iter().rev().find(|q| q.position < pos).map(|q| q.track_id.clone()),
+        None => queue.first().map(|q| q.track_id.clone()),
+    }
+}
+
+// Initialize R2 client. Public so the out-of-module storage worker
+// (src/bin/storage_worker.rs) can build the same client from the same env vars.
+pub fn get_r2_client() -> Result<S3Client, String> {
     let endpoint = env::var("R2_ENDPOINT").map_err(|_| "R2_ENDPOINT not set".to_string())?;
     let access_key_id = env::var("R2_ACCESS_KEY_ID").map_err(|_| "R2_ACCESS_KEY_ID not set".to_string())?;
     let secret_access_key = env::var("R2_SECRET_ACCESS_KEY").map_err(|_| "R2_SECRET_ACCESS_KEY not set".to_string())?;
@@ -111,6 +388,44 @@ fn get_r2_client() -> Result<S3Client, String> {
     Ok(S3Client::from_conf(config))
 }
 
+pub fn get_r2_bucket() -> Result<String, String> {
+    env::var("R2_BUCKET").map_err(|_| "R2_BUCKET not set".to_string())
+}
+
+// What a StorageRequest asks the worker to do
+#[derive(Clone, Copy, PartialEq, SpacetimeType)]
+pub enum StorageOperation {
+    GetPresignedUrl,
+    DeleteObject,
+}
+
+// StorageRequest table - work items reducers enqueue for the async R2 worker.
+// `file_path` is copied in at enqueue time rather than looked up by the
+// worker, since a DeleteObject request is enqueued in the same reducer call
+// that removes the Track row it refers to.
+#[table(name = storage_request, public)]
+#[derive(Clone)]
+pub struct StorageRequest {
+    #[primary_key]
+    pub id: String,
+    pub user_id: String,
+    pub track_id: String,
+    pub file_path: String,
+    pub operation: StorageOperation,
+    pub created_at: u64,
+}
+
+// StorageResponse table - the outcome of a StorageRequest, written by the worker
+#[table(name = storage_response, public)]
+#[derive(Clone)]
+pub struct StorageResponse {
+    #[primary_key]
+    pub request_id: String,
+    pub presigned_url: Option<String>,
+    pub expires_at: Option<u64>,
+    pub error: Option<String>,
+}
+
 #[reducer]
 pub fn init(_ctx: &ReducerContext) {
     info!("Initializing music server module");
@@ -196,6 +511,52 @@ pub fn search_tracks(ctx: &ReducerContext, query: String) {
     }
 }
 
+#[reducer]
+pub fn search_tracks_fuzzy(ctx: &ReducerContext, query: String) {
+    let user_id = ctx.sender.to_string();
+    let query_trigrams = trigrams(&query);
+    let search_result_table = ctx.db.search_result();
+
+    // Clear this caller's previous results before writing the new ranking
+    let previous: Vec<SearchResult> = search_result_table.iter()
+        .filter(|r| r.user_id == user_id)
+        .collect();
+
+    for result in previous {
+        search_result_table.delete(result);
+    }
+
+    let mut scored: Vec<(Track, f64)> = ctx.db.track().iter()
+        .map(|track| {
+            let score = [
+                Some(&track.title),
+                Some(&track.artist),
+                Some(&track.album),
+                track.genre.as_ref(),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|field| trigram_similarity(&query_trigrams, &trigrams(field)))
+            .fold(0.0, f64::max);
+
+            (track, score)
+        })
+        .filter(|(_, score)| *score > FUZZY_SEARCH_THRESHOLD)
+        .collect();
+
+    scored.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+
+    for (track, score) in scored {
+        search_result_table.insert(SearchResult {
+            id: generate_id(),
+            user_id: user_id.clone(),
+            track_id: track.id,
+            score,
+            date_added: current_timestamp(),
+        });
+    }
+}
+
 #[reducer]
 pub fn create_playlist(ctx: &ReducerContext, name: String, description: Option<String>, is_public: bool) {
     let playlist_id = generate_id();
@@ -383,45 +744,405 @@ pub fn get_favorite_tracks(ctx: &ReducerContext) {
     }
 }
 
+#[reducer]
+pub fn get_recommendations(ctx: &ReducerContext, limit: u32) {
+    let user_id = ctx.sender.to_string();
+    let track_table = ctx.db.track();
+    let recommendation_table = ctx.db.recommendation();
+
+    let favorites: Vec<UserFavorite> = ctx.db.user_favorite().iter()
+        .filter(|fav| fav.user_id == user_id)
+        .collect();
+
+    let favorited_track_ids: HashSet<String> = favorites.iter()
+        .map(|fav| fav.track_id.clone())
+        .collect();
+
+    let favorited_tracks: Vec<Track> = track_table.iter()
+        .filter(|t| favorited_track_ids.contains(&t.id))
+        .collect();
+
+    // Tally genre frequency and collect the artists and albums across the caller's favorites to build a taste profile
+    let mut genre_counts: HashMap<String, u32> = HashMap::new();
+    let mut favorited_artists: HashSet<String> = HashSet::new();
+    let mut favorited_albums: HashSet<String> = HashSet::new();
+
+    for track in &favorited_tracks {
+        if let Some(genre) = &track.genre {
+            *genre_counts.entry(genre.clone()).or_insert(0) += 1;
+        }
+        favorited_artists.insert(track.artist.clone());
+        favorited_albums.insert(track.album.clone());
+    }
+
+    let mut ranked_genres: Vec<(String, u32)> = genre_counts.into_iter().collect();
+    ranked_genres.sort_by(|a, b| b.1.cmp(&a.1));
+    let top_genres: HashSet<String> = ranked_genres.into_iter()
+        .take(TOP_GENRE_COUNT)
+        .map(|(genre, _)| genre)
+        .collect();
+
+    let queued_track_ids: HashSet<String> = ctx.db.play_queue().iter()
+        .filter(|q| q.user_id == user_id)
+        .map(|q| q.track_id)
+        .collect();
+
+    let now = current_timestamp();
+
+    let mut scored: Vec<(Track, f64)> = track_table.iter()
+        .filter(|t| !favorited_track_ids.contains(&t.id) && !queued_track_ids.contains(&t.id))
+        .map(|track| {
+            let mut score = 0.0;
+
+            if track.genre.as_ref().map_or(false, |g| top_genres.contains(g)) {
+                score += RECOMMENDATION_GENRE_WEIGHT;
+            }
+
+            if favorited_artists.contains(&track.artist) {
+                score += RECOMMENDATION_ARTIST_WEIGHT;
+            }
+
+            if favorited_albums.contains(&track.album) {
+                score += RECOMMENDATION_ALBUM_WEIGHT;
+            }
+
+            if now.saturating_sub(track.date_added) < RECOMMENDATION_RECENCY_WINDOW_SECONDS {
+                score += RECOMMENDATION_RECENCY_BONUS;
+            }
+
+            (track, score)
+        })
+        .filter(|(_, score)| *score > 0.0)
+        .collect();
+
+    scored.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+
+    // Clear this caller's previous recommendations before writing the new ranking
+    let previous: Vec<Recommendation> = recommendation_table.iter()
+        .filter(|r| r.user_id == user_id)
+        .collect();
+
+    for recommendation in previous {
+        recommendation_table.delete(recommendation);
+    }
+
+    for (track, score) in scored.into_iter().take(limit as usize) {
+        recommendation_table.insert(Recommendation {
+            id: generate_id(),
+            user_id: user_id.clone(),
+            track_id: track.id,
+            score,
+        });
+    }
+}
+
+#[reducer]
+pub fn start_playback(ctx: &ReducerContext, source_id: String) {
+    let user_id = ctx.sender.to_string();
+    let playback_state_table = ctx.db.playback_state();
+
+    populate_play_queue(ctx, &user_id, &source_id);
+
+    // Mark the track we're about to start on as played so shuffle can't
+    // immediately re-pick it on the very next play_next
+    let first_entry = ctx.db.play_queue().iter()
+        .filter(|q| q.user_id == user_id)
+        .min_by_key(|q| q.position);
+
+    let first_track_id = first_entry.map(|entry| mark_played(ctx, entry));
+
+    // Preserve the user's existing shuffle/repeat preferences across sources
+    let existing_states: Vec<PlaybackState> = playback_state_table.iter()
+        .filter(|s| s.user_id == user_id)
+        .collect();
+
+    let (shuffle, repeat_mode) = if existing_states.is_empty() {
+        (false, RepeatMode::Off)
+    } else {
+        playback_state_table.delete(existing_states[0].clone());
+        (existing_states[0].shuffle, existing_states[0].repeat_mode)
+    };
+
+    playback_state_table.insert(PlaybackState {
+        user_id,
+        current_track_id: first_track_id,
+        position_seconds: 0,
+        is_playing: true,
+        repeat_mode,
+        shuffle,
+    });
+}
+
+#[reducer]
+pub fn play_next(ctx: &ReducerContext) {
+    let user_id = ctx.sender.to_string();
+    let playback_state_table = ctx.db.playback_state();
+
+    let states: Vec<PlaybackState> = playback_state_table.iter()
+        .filter(|s| s.user_id == user_id)
+        .collect();
+
+    if states.is_empty() {
+        error!("No playback state for user {}", user_id);
+        return;
+    }
+
+    let mut state = states[0].clone();
+    state.current_track_id = advance_queue(ctx, &user_id, &state);
+    state.position_seconds = 0;
+    state.is_playing = state.current_track_id.is_some();
+
+    playback_state_table.delete(states[0].clone());
+    playback_state_table.insert(state);
+}
+
+#[reducer]
+pub fn play_previous(ctx: &ReducerContext) {
+    let user_id = ctx.sender.to_string();
+    let playback_state_table = ctx.db.playback_state();
+
+    let states: Vec<PlaybackState> = playback_state_table.iter()
+        .filter(|s| s.user_id == user_id)
+        .collect();
+
+    if states.is_empty() {
+        error!("No playback state for user {}", user_id);
+        return;
+    }
+
+    let mut state = states[0].clone();
+    state.current_track_id = retreat_queue(ctx, &user_id, &state);
+    state.position_seconds = 0;
+    state.is_playing = state.current_track_id.is_some();
+
+    playback_state_table.delete(states[0].clone());
+    playback_state_table.insert(state);
+}
+
+#[reducer]
+pub fn set_shuffle(ctx: &ReducerContext, shuffle: bool) {
+    let user_id = ctx.sender.to_string();
+    let playback_state_table = ctx.db.playback_state();
+
+    let states: Vec<PlaybackState> = playback_state_table.iter()
+        .filter(|s| s.user_id == user_id)
+        .collect();
+
+    if states.is_empty() {
+        error!("No playback state for user {}", user_id);
+        return;
+    }
+
+    let mut state = states[0].clone();
+    state.shuffle = shuffle;
+
+    playback_state_table.delete(states[0].clone());
+    playback_state_table.insert(state);
+}
+
+#[reducer]
+pub fn set_repeat(ctx: &ReducerContext, repeat_mode: RepeatMode) {
+    let user_id = ctx.sender.to_string();
+    let playback_state_table = ctx.db.playback_state();
+
+    let states: Vec<PlaybackState> = playback_state_table.iter()
+        .filter(|s| s.user_id == user_id)
+        .collect();
+
+    if states.is_empty() {
+        error!("No playback state for user {}", user_id);
+        return;
+    }
+
+    let mut state = states[0].clone();
+    state.repeat_mode = repeat_mode;
+
+    playback_state_table.delete(states[0].clone());
+    playback_state_table.insert(state);
+}
+
+#[reducer]
+pub fn record_play(ctx: &ReducerContext, track_id: String, ms_played: u32) {
+    let track_table = ctx.db.track();
+
+    let tracks: Vec<Track> = track_table.iter()
+        .filter(|t| t.id == track_id)
+        .collect();
+
+    if tracks.is_empty() {
+        error!("Track with ID {} not found", track_id);
+        return;
+    }
+
+    ctx.db.play_event().insert(PlayEvent {
+        id: generate_id(),
+        user_id: ctx.sender.to_string(),
+        track_id,
+        played_at: current_timestamp(),
+        ms_played,
+    });
+}
+
+#[reducer]
+pub fn get_top_tracks(ctx: &ReducerContext, period_days: u32, limit: u32) {
+    let user_id = ctx.sender.to_string();
+    let top_track_table = ctx.db.top_track();
+
+    let window_start = current_timestamp().saturating_sub(period_days as u64 * 86400);
+
+    let recent_plays: Vec<PlayEvent> = ctx.db.play_event().iter()
+        .filter(|p| p.user_id == user_id && p.played_at >= window_start)
+        .collect();
+
+    let mut play_counts: HashMap<String, u32> = HashMap::new();
+    let mut ms_played: HashMap<String, u64> = HashMap::new();
+
+    for play in recent_plays {
+        *play_counts.entry(play.track_id.clone()).or_insert(0) += 1;
+        *ms_played.entry(play.track_id.clone()).or_insert(0) += play.ms_played as u64;
+    }
+
+    let mut leaderboard: Vec<(String, u32, u64)> = play_counts.into_iter()
+        .map(|(track_id, play_count)| {
+            let total_ms_played = *ms_played.get(&track_id).unwrap_or(&0);
+            (track_id, play_count, total_ms_played)
+        })
+        .collect();
+
+    // Rank by play count, breaking ties with total listening time
+    leaderboard.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.cmp(&a.2)));
+
+    // Clear this caller's previous leaderboard before writing the new ranking
+    let previous: Vec<TopTrack> = top_track_table.iter()
+        .filter(|t| t.user_id == user_id)
+        .collect();
+
+    for top_track in previous {
+        top_track_table.delete(top_track);
+    }
+
+    for (track_id, play_count, total_ms_played) in leaderboard.into_iter().take(limit as usize) {
+        top_track_table.insert(TopTrack {
+            id: generate_id(),
+            user_id: user_id.clone(),
+            track_id,
+            play_count,
+            total_ms_played,
+        });
+    }
+}
+
 #[reducer]
 pub fn delete_track(ctx: &ReducerContext, track_id: String) {
     let track_table = ctx.db.track();
     let playlist_track_table = ctx.db.playlist_track();
     let favorite_table = ctx.db.user_favorite();
-    
+    let play_event_table = ctx.db.play_event();
+
     // Check if track exists
     let tracks: Vec<Track> = track_table.iter()
         .filter(|t| t.id == track_id)
         .collect();
-    
+
     if tracks.is_empty() {
         error!("Track with ID {} not found", track_id);
         return;
     }
-    
+
     // Delete all playlist entries for this track
     let playlist_tracks: Vec<PlaylistTrack> = playlist_track_table.iter()
         .filter(|pt| pt.track_id == track_id)
         .collect();
-    
+
     for pt in playlist_tracks {
         playlist_track_table.delete(pt);
     }
-    
+
     // Delete all user favorites for this track
     let favorites: Vec<UserFavorite> = favorite_table.iter()
         .filter(|fav| fav.track_id == track_id)
         .collect();
-    
+
     for fav in favorites {
         favorite_table.delete(fav);
     }
-    
+
+    // Delete all play history for this track
+    let play_events: Vec<PlayEvent> = play_event_table.iter()
+        .filter(|p| p.track_id == track_id)
+        .collect();
+
+    for play_event in play_events {
+        play_event_table.delete(play_event);
+    }
+
+    // Enqueue R2 cleanup before the Track row disappears, since the worker
+    // that drains this can't look the file path up afterwards
+    ctx.db.storage_request().insert(StorageRequest {
+        id: generate_id(),
+        user_id: ctx.sender.to_string(),
+        track_id: track_id.clone(),
+        file_path: tracks[0].file_path.clone(),
+        operation: StorageOperation::DeleteObject,
+        created_at: current_timestamp(),
+    });
+
     // Delete the track itself
     track_table.delete(tracks[0].clone());
-    
-    // Note: We can't delete the file from R2 here since we can't use async in reducers
-    // This would need to be handled separately
+}
+
+#[reducer]
+pub fn request_stream_url(ctx: &ReducerContext, track_id: String) {
+    let track_table = ctx.db.track();
+
+    let tracks: Vec<Track> = track_table.iter()
+        .filter(|t| t.id == track_id)
+        .collect();
+
+    if tracks.is_empty() {
+        error!("Track with ID {} not found", track_id);
+        return;
+    }
+
+    ctx.db.storage_request().insert(StorageRequest {
+        id: generate_id(),
+        user_id: ctx.sender.to_string(),
+        track_id,
+        file_path: tracks[0].file_path.clone(),
+        operation: StorageOperation::GetPresignedUrl,
+        created_at: current_timestamp(),
+    });
+}
+
+#[reducer]
+pub fn submit_storage_response(ctx: &ReducerContext, request_id: String, presigned_url: Option<String>, expires_at: Option<u64>, error: Option<String>) {
+    let storage_request_table = ctx.db.storage_request();
+    let storage_response_table = ctx.db.storage_response();
+
+    let existing: Vec<StorageResponse> = storage_response_table.iter()
+        .filter(|r| r.request_id == request_id)
+        .collect();
+
+    for response in existing {
+        storage_response_table.delete(response);
+    }
+
+    storage_response_table.insert(StorageResponse {
+        request_id: request_id.clone(),
+        presigned_url,
+        expires_at,
+        error,
+    });
+
+    // The request has now been handled; drop it so storage_request doesn't
+    // grow without bound as the worker drains it
+    let handled_requests: Vec<StorageRequest> = storage_request_table.iter()
+        .filter(|r| r.id == request_id)
+        .collect();
+
+    for request in handled_requests {
+        storage_request_table.delete(request);
+    }
 }
 
 #[reducer]
@@ -482,11 +1203,73 @@ pub fn get_stats(ctx: &ReducerContext) {
         .iter()
         .map(|t| t.file_size_bytes)
         .sum();
-    
+
+    // Count total plays and listening time across all history
+    let play_count = ctx.db.play_event().count();
+    let total_ms_played: u64 = ctx.db.play_event()
+        .iter()
+        .map(|p| p.ms_played as u64)
+        .sum();
+
     // Log the stats
     info!("Track count: {}", track_count);
     info!("Playlist count: {}", playlist_count);
     info!("User count: {}", user_count);
     info!("Total duration: {} seconds", total_duration);
     info!("Total size: {} bytes", total_size);
+    info!("Play count: {}", play_count);
+    info!("Total listening time: {} ms", total_ms_played);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trigrams_pads_with_two_leading_spaces_and_one_trailing() {
+        let expected: HashSet<String> = ["  c", " ca", "cat", "at "]
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(trigrams("cat"), expected);
+    }
+
+    #[test]
+    fn trigram_similarity_of_known_pair() {
+        // "cat" vs "cats": {"  c"," ca","cat","at "} vs {"  c"," ca","cat","ats","ts "}
+        // intersection = {"  c"," ca","cat"} (3), union = 6 => 3/6
+        let similarity = trigram_similarity(&trigrams("cat"), &trigrams("cats"));
+        assert!((similarity - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn trigram_similarity_of_identical_strings_is_one() {
+        let similarity = trigram_similarity(&trigrams("beatles"), &trigrams("beatles"));
+        assert!((similarity - 1.0).abs() < f64::EPSILON);
+    }
+
+    fn play_queue_entry(position: u32, played: bool) -> PlayQueue {
+        PlayQueue {
+            id: format!("entry-{}", position),
+            user_id: "user-1".to_string(),
+            track_id: format!("track-{}", position),
+            position,
+            played,
+        }
+    }
+
+    #[test]
+    fn queue_is_unstarted_when_nothing_has_been_played() {
+        let queue = vec![play_queue_entry(0, false), play_queue_entry(1, false)];
+        assert!(is_queue_unstarted(&queue));
+    }
+
+    #[test]
+    fn queue_is_not_unstarted_once_repeat_off_has_finished_it() {
+        // Every entry played means a prior play_next walked off the end
+        // under RepeatMode::Off, not that playback never started.
+        let queue = vec![play_queue_entry(0, true), play_queue_entry(1, true)];
+        assert!(!is_queue_unstarted(&queue));
+    }
 }